@@ -1,16 +1,80 @@
-use anyhow::{self, bail, Context, Ok};
+use anyhow::{self, Context, Ok};
 use csv::Trim;
 use decimal::d128;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, io::{Write}};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::Write,
+};
 
 type ClientId = u16;
 type TransactionId = u32;
 type Money = d128;
 
+/// Business-rule violations raised by [`Ledger::process_transaction`]. Kept distinct from
+/// the `anyhow` errors used elsewhere (file I/O, CSV parsing) so callers can match on the
+/// specific rule that was violated instead of string-matching an error message.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LedgerError {
+    MissingAmount,
+    NotEnoughFunds,
+    UnknownTx,
+    AlreadyDisputed,
+    NotDisputed,
+    FrozenAccount,
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            LedgerError::MissingAmount => "transaction must have an 'amount' value",
+            LedgerError::NotEnoughFunds => "funds are not sufficient for withdrawal",
+            LedgerError::UnknownTx => "cannot reference an unknown transaction",
+            LedgerError::AlreadyDisputed => "transaction is already disputed",
+            LedgerError::NotDisputed => "transaction is not disputed",
+            LedgerError::FrozenAccount => "account is frozen",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
 pub struct Ledger {
     accounts: HashMap<ClientId, Account>,
-    deposit_transactions_cache: HashMap<TransactionId, Money>,
+    tx_states: HashMap<(ClientId, TransactionId), TxRecord>,
+    // assumption was that 'locked' has no behavioral effect (see readme.txt); now configurable
+    // so a frozen account actually stops moving money, with an opt-out for old callers.
+    reject_frozen_account_transactions: bool,
+}
+
+/// Lifecycle of a disputable transaction. Allowed transitions:
+/// `Processed -> Disputed`, `Disputed -> Resolved`, `Disputed -> ChargedBack`,
+/// and `Resolved -> Disputed` (a resolved dispute can be reopened).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TxState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+/// Which side of the ledger a disputable transaction moved money on, so dispute/resolve/
+/// chargeback can apply the held/available/total math in the right direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TxKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// Bookkeeping kept per disputable transaction so `dispute`/`resolve`/`chargeback`
+/// can enforce legal transitions and restore/remove the correct amount.
+#[derive(Debug)]
+struct TxRecord {
+    kind: TxKind,
+    amount: Money,
+    state: TxState,
 }
 
 #[derive(Debug, Serialize)]
@@ -20,9 +84,6 @@ struct Account {
     held: Money,
     total: Money,
     locked: bool,
-
-    #[serde(skip_serializing)]
-    disputed_txs: HashMap<TransactionId, Money>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -66,93 +127,200 @@ impl Account {
             held: Default::default(),
             total: Default::default(),
             locked: false,
-            disputed_txs: Default::default(),
         }
     }
 }
 
 impl Ledger {
-    fn new() -> Ledger {
+    /// `reject_frozen_account_transactions` controls whether deposits/withdrawals are
+    /// rejected once an account has been locked by a chargeback. Pass `false` to keep the
+    /// old permissive behavior where `locked` is purely an indicator.
+    fn new(reject_frozen_account_transactions: bool) -> Ledger {
         Ledger {
             accounts: Default::default(),
-            deposit_transactions_cache: Default::default(),
+            tx_states: Default::default(),
+            reject_frozen_account_transactions,
         }
     }
 
-    fn process_transaction(&mut self, transaction: &Transaction) -> anyhow::Result<()> {
+    fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), LedgerError> {
         let client = transaction.client;
-        let account = self.accounts.entry(client).or_insert(Account::new(client));
+        let reject_frozen = self.reject_frozen_account_transactions;
 
         match transaction.type_ {
-            // assumption: 'locked' state is only an indicator of chargeback and doesn't impact any operation - see all assumptions in readme.txt
             TransactionType::Deposit => {
-                let amount = transaction
-                    .amount
-                    .context("'deposit' transaction must have 'amount' value")?;
+                let amount = transaction.amount.ok_or(LedgerError::MissingAmount)?;
+                let account = self.accounts.entry(client).or_insert(Account::new(client));
+                if reject_frozen && account.locked {
+                    return Err(LedgerError::FrozenAccount);
+                }
                 account.available += amount;
                 account.total += amount;
-                // assumption: only 'deposit' transactions can be disputed
-                self.deposit_transactions_cache
-                    .insert(transaction.id, amount);
+                self.tx_states.insert(
+                    (client, transaction.id),
+                    TxRecord {
+                        kind: TxKind::Deposit,
+                        amount,
+                        state: TxState::Processed,
+                    },
+                );
             }
             TransactionType::Withdrawal => {
-                let amount = transaction
-                    .amount
-                    .context("'withdrawal' transaction must have 'amount' value")?;
+                let amount = transaction.amount.ok_or(LedgerError::MissingAmount)?;
+                let account = self.accounts.entry(client).or_insert(Account::new(client));
+                if reject_frozen && account.locked {
+                    return Err(LedgerError::FrozenAccount);
+                }
                 if account.available >= amount && account.total >= amount {
                     account.available -= amount;
                     account.total -= amount;
                 } else {
-                    bail!("funds are not sufficient for withdrawal")
+                    return Err(LedgerError::NotEnoughFunds);
                 }
+                self.tx_states.insert(
+                    (client, transaction.id),
+                    TxRecord {
+                        kind: TxKind::Withdrawal,
+                        amount,
+                        state: TxState::Processed,
+                    },
+                );
             }
             TransactionType::Dispute => {
-                if let Some(amount) = self.deposit_transactions_cache.remove(&transaction.id) {
-                    account.disputed_txs.insert(transaction.id, amount);
-                    account.held += amount;
-                    account.available -= amount; // the balance can become negative - see all assumptions in readme.txt
+                // keying by (client, tx) means a tx id referenced with the wrong client
+                // simply doesn't match any record here, so it's rejected the same way an
+                // unknown tx id would be - no account is created or mutated for the disputer.
+                let record = self
+                    .tx_states
+                    .get_mut(&(client, transaction.id))
+                    .ok_or(LedgerError::UnknownTx)?;
+                match record.state {
+                    TxState::Processed | TxState::Resolved => {
+                        let account = self.accounts.get_mut(&client).expect("account must exist for a recorded transaction");
+                        match record.kind {
+                            TxKind::Deposit => {
+                                account.held += record.amount;
+                                account.available -= record.amount; // the balance can become negative - see all assumptions in readme.txt
+                            }
+                            TxKind::Withdrawal => {
+                                // the funds already left on withdrawal; provisionally restore
+                                // them to `total` but hold them pending the dispute's outcome
+                                account.held += record.amount;
+                                account.total += record.amount;
+                            }
+                        }
+                        record.state = TxState::Disputed;
+                    }
+                    TxState::Disputed | TxState::ChargedBack => return Err(LedgerError::AlreadyDisputed),
                 }
             }
             TransactionType::Resolve => {
-                if let Some(amount) = account.disputed_txs.remove(&transaction.id) {
-                    account.held -= amount;
-                    account.available += amount;
-                };
+                let record = self
+                    .tx_states
+                    .get_mut(&(client, transaction.id))
+                    .ok_or(LedgerError::NotDisputed)?;
+                if record.state != TxState::Disputed {
+                    return Err(LedgerError::NotDisputed);
+                }
+                let account = self.accounts.get_mut(&client).expect("account must exist for a recorded transaction");
+                match record.kind {
+                    TxKind::Deposit => {
+                        account.held -= record.amount;
+                        account.available += record.amount;
+                    }
+                    TxKind::Withdrawal => {
+                        // dispute rejected: the withdrawal stands, undo the provisional restore
+                        account.held -= record.amount;
+                        account.total -= record.amount;
+                    }
+                }
+                record.state = TxState::Resolved;
             }
             TransactionType::Chargeback => {
-                if let Some(amount) = account.disputed_txs.remove(&transaction.id) {
-                    account.held -= amount;
-                    account.total -= amount;
-                    account.locked = true;
-                };
+                let record = self
+                    .tx_states
+                    .get_mut(&(client, transaction.id))
+                    .ok_or(LedgerError::NotDisputed)?;
+                if record.state != TxState::Disputed {
+                    return Err(LedgerError::NotDisputed);
+                }
+                let account = self.accounts.get_mut(&client).expect("account must exist for a recorded transaction");
+                match record.kind {
+                    TxKind::Deposit => {
+                        account.held -= record.amount;
+                        account.total -= record.amount;
+                    }
+                    TxKind::Withdrawal => {
+                        // dispute upheld: the withdrawal is reversed for good
+                        account.held -= record.amount;
+                        account.available += record.amount;
+                    }
+                }
+                account.locked = true;
+                record.state = TxState::ChargedBack;
             }
         }
 
-        Ok(())
+        Result::Ok(())
     }
 }
 
-pub fn load_transactions(transactions_fpath: &str) -> anyhow::Result<Ledger> {
-    let mut ledger = Ledger::new();
+/// Controls how [`load_transactions`] reacts to a malformed row or a rejected transaction.
+pub enum FaultTolerance {
+    /// Abort on the first parse failure or rejected transaction.
+    Strict,
+    /// Skip the faulty row, record it as a diagnostic and keep processing the rest of the file.
+    SkipFaultyRows,
+}
+
+/// A row that couldn't be parsed or processed, along with its 1-based line number in the
+/// input file (the header occupies line 1, so the first data row is line 2).
+pub type LoadDiagnostic = (usize, anyhow::Error);
+
+pub fn load_transactions(
+    transactions_fpath: &str,
+    fault_tolerance: FaultTolerance,
+) -> anyhow::Result<(Ledger, Vec<LoadDiagnostic>)> {
+    let mut ledger = Ledger::new(true);
+    let mut diagnostics = Vec::new();
 
     let mut reader = csv::ReaderBuilder::new()
         .trim(Trim::All)
         .from_path(transactions_fpath)
         .context(transactions_fpath.to_string())?;
 
-    for line in reader.deserialize() {
-        let transaction: Transaction = line?;
-        ledger
-            .process_transaction(&transaction)
-            .context(format!("cannot process transaction: id={}", transaction.id))?;
+    for (row_index, line) in reader.deserialize::<Transaction>().enumerate() {
+        let line_number = row_index + 2;
+
+        let result: anyhow::Result<()> = (|| {
+            let transaction = line?;
+            ledger.process_transaction(&transaction)?;
+            Ok(())
+        })();
+
+        if let Err(error) = result {
+            match fault_tolerance {
+                FaultTolerance::Strict => {
+                    return Err(error.context(format!("cannot process line {line_number}")))
+                }
+                FaultTolerance::SkipFaultyRows => diagnostics.push((line_number, error)),
+            }
+        }
     }
 
-    Ok(ledger)
+    Ok((ledger, diagnostics))
 }
 
 pub fn output_accounts<W: Write>(ledger: &Ledger, output: &mut W) -> anyhow::Result<()> {
     let mut writer = csv::Writer::from_writer(output);
-    for account in ledger.accounts.values() {
+
+    // sorted by client id so output is deterministic regardless of HashMap iteration order
+    let sorted_accounts: BTreeMap<ClientId, &Account> = ledger
+        .accounts
+        .iter()
+        .map(|(client, account)| (*client, account))
+        .collect();
+    for account in sorted_accounts.values() {
         writer.serialize(account)?;
     }
 
@@ -162,14 +330,17 @@ pub fn output_accounts<W: Write>(ledger: &Ledger, output: &mut W) -> anyhow::Res
 
 #[cfg(test)]
 mod tests {
-    use super::{Account, ClientId, Ledger, Money, Transaction, TransactionId, TransactionType};
+    use super::{
+        Account, ClientId, FaultTolerance, Ledger, Money, Transaction, TransactionId,
+        TransactionType,
+    };
     use decimal::d128;
 
     const CLIENT1: ClientId = 1;
 
     #[test]
     fn deposit_partial_withdraw_success() {
-        let mut ledger = Ledger::new();
+        let mut ledger = Ledger::new(true);
 
         {
             // deposit
@@ -214,7 +385,7 @@ mod tests {
 
     #[test]
     fn excessive_withdraw_should_fail() {
-        let mut ledger = Ledger::new();
+        let mut ledger = Ledger::new(true);
 
         {
             // deposit
@@ -255,7 +426,7 @@ mod tests {
 
     #[test]
     fn dispute_resolve_success() {
-        let mut ledger = Ledger::new();
+        let mut ledger = Ledger::new(true);
 
         {
             // deposit 1
@@ -337,7 +508,7 @@ mod tests {
             );
         }
         {
-            // duplicate 'resolve'
+            // duplicate 'resolve' is rejected: the tx is no longer in a Disputed state
             let tx = Transaction {
                 type_: TransactionType::Resolve,
                 client: CLIENT1,
@@ -345,7 +516,11 @@ mod tests {
                 amount: None,
             };
 
-            ledger.process_transaction(&tx).unwrap();
+            let error_text = ledger
+                .process_transaction(&tx)
+                .expect_err("expected Error")
+                .to_string();
+            assert_eq!(error_text, "transaction is not disputed");
 
             assert_account(
                 &ledger.accounts[&CLIENT1],
@@ -357,9 +532,72 @@ mod tests {
         }
     }
 
+    #[test]
+    fn dispute_after_resolve_is_allowed_again() {
+        let mut ledger = Ledger::new(true);
+
+        let tx = Transaction {
+            type_: TransactionType::Deposit,
+            client: CLIENT1,
+            id: 1,
+            amount: Some(d128!(0.0005)),
+        };
+        ledger.process_transaction(&tx).unwrap();
+
+        for tx_type in [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Dispute,
+        ] {
+            let tx = Transaction {
+                type_: tx_type,
+                client: CLIENT1,
+                id: 1,
+                amount: None,
+            };
+            ledger.process_transaction(&tx).unwrap();
+        }
+
+        // re-disputed: the original deposit amount is held again
+        assert_account(
+            &ledger.accounts[&CLIENT1],
+            d128!(0),
+            d128!(0.0005),
+            d128!(0.0005),
+            false,
+        );
+    }
+
+    #[test]
+    fn dispute_while_already_disputed_is_rejected() {
+        let mut ledger = Ledger::new(true);
+
+        let tx = Transaction {
+            type_: TransactionType::Deposit,
+            client: CLIENT1,
+            id: 1,
+            amount: Some(d128!(0.0005)),
+        };
+        ledger.process_transaction(&tx).unwrap();
+
+        let tx = Transaction {
+            type_: TransactionType::Dispute,
+            client: CLIENT1,
+            id: 1,
+            amount: None,
+        };
+        ledger.process_transaction(&tx).unwrap();
+
+        let error_text = ledger
+            .process_transaction(&tx)
+            .expect_err("expected Error")
+            .to_string();
+        assert_eq!(error_text, "transaction is already disputed");
+    }
+
     #[test]
     fn dispute_chargeback_success() {
-        let mut ledger = Ledger::new();
+        let mut ledger = Ledger::new(true);
 
         {
             // deposit 1
@@ -444,7 +682,7 @@ mod tests {
 
     #[test]
     fn negative_balance_after_chargeback() {
-        let mut ledger = Ledger::new();
+        let mut ledger = Ledger::new(true);
 
         {
             // deposit
@@ -528,8 +766,8 @@ mod tests {
     }
 
     #[test]
-    fn invalid_dispute_reference_ignored() {
-        let mut ledger = Ledger::new();
+    fn invalid_dispute_reference_rejected() {
+        let mut ledger = Ledger::new(true);
         let not_existing_tx_id: TransactionId = 999;
 
         {
@@ -552,7 +790,7 @@ mod tests {
             );
         }
 
-        for tx_type in vec![
+        for tx_type in [
             TransactionType::Dispute,
             TransactionType::Resolve,
             TransactionType::Chargeback,
@@ -564,7 +802,9 @@ mod tests {
                 amount: None,
             };
 
-            ledger.process_transaction(&tx).unwrap();
+            ledger
+                .process_transaction(&tx)
+                .expect_err("expected Error referencing an unknown transaction");
             // balance shouldn't change
             assert_account(
                 &ledger.accounts[&CLIENT1],
@@ -576,6 +816,267 @@ mod tests {
         }
     }
 
+    #[test]
+    fn dispute_of_another_clients_transaction_is_rejected() {
+        const CLIENT2: ClientId = 2;
+        let mut ledger = Ledger::new(true);
+
+        let tx = Transaction {
+            type_: TransactionType::Deposit,
+            client: CLIENT1,
+            id: 1,
+            amount: Some(d128!(0.0005)),
+        };
+        ledger.process_transaction(&tx).unwrap();
+
+        // client 2 tries to dispute client 1's deposit
+        let tx = Transaction {
+            type_: TransactionType::Dispute,
+            client: CLIENT2,
+            id: 1,
+            amount: None,
+        };
+        ledger
+            .process_transaction(&tx)
+            .expect_err("expected Error: transaction belongs to a different client");
+
+        // client 1's balance is untouched and no spurious account was created for client 2
+        assert_account(
+            &ledger.accounts[&CLIENT1],
+            d128!(0.0005),
+            d128!(0),
+            d128!(0.0005),
+            false,
+        );
+        assert!(!ledger.accounts.contains_key(&CLIENT2));
+    }
+
+    #[test]
+    fn frozen_account_rejects_deposit_and_withdrawal() {
+        let mut ledger = Ledger::new(true);
+
+        let tx = Transaction {
+            type_: TransactionType::Deposit,
+            client: CLIENT1,
+            id: 1,
+            amount: Some(d128!(0.0005)),
+        };
+        ledger.process_transaction(&tx).unwrap();
+
+        for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+            let tx = Transaction {
+                type_: tx_type,
+                client: CLIENT1,
+                id: 1,
+                amount: None,
+            };
+            ledger.process_transaction(&tx).unwrap();
+        }
+        assert!(ledger.accounts[&CLIENT1].locked);
+
+        let tx = Transaction {
+            type_: TransactionType::Deposit,
+            client: CLIENT1,
+            id: 2,
+            amount: Some(d128!(0.0001)),
+        };
+        let error_text = ledger
+            .process_transaction(&tx)
+            .expect_err("expected Error")
+            .to_string();
+        assert_eq!(error_text, "account is frozen");
+
+        let tx = Transaction {
+            type_: TransactionType::Withdrawal,
+            client: CLIENT1,
+            id: 3,
+            amount: Some(d128!(0.0001)),
+        };
+        let error_text = ledger
+            .process_transaction(&tx)
+            .expect_err("expected Error")
+            .to_string();
+        assert_eq!(error_text, "account is frozen");
+    }
+
+    #[test]
+    fn permissive_mode_allows_deposits_after_chargeback() {
+        let mut ledger = Ledger::new(false);
+
+        let tx = Transaction {
+            type_: TransactionType::Deposit,
+            client: CLIENT1,
+            id: 1,
+            amount: Some(d128!(0.0005)),
+        };
+        ledger.process_transaction(&tx).unwrap();
+
+        for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+            let tx = Transaction {
+                type_: tx_type,
+                client: CLIENT1,
+                id: 1,
+                amount: None,
+            };
+            ledger.process_transaction(&tx).unwrap();
+        }
+        assert!(ledger.accounts[&CLIENT1].locked);
+
+        let tx = Transaction {
+            type_: TransactionType::Deposit,
+            client: CLIENT1,
+            id: 2,
+            amount: Some(d128!(0.0001)),
+        };
+        ledger.process_transaction(&tx).unwrap();
+
+        assert_account(
+            &ledger.accounts[&CLIENT1],
+            d128!(0.0001),
+            d128!(0),
+            d128!(0.0001),
+            true,
+        );
+    }
+
+    #[test]
+    fn load_transactions_skips_faulty_rows_when_tolerant() {
+        let fpath = std::env::temp_dir().join(format!(
+            "process_transactions_test_{}.csv",
+            std::process::id()
+        ));
+        std::fs::write(
+            &fpath,
+            "type,client,tx,amount\n\
+             deposit,1,1,1.5\n\
+             withdrawal,1,2,5.0\n\
+             deposit,1,3,0.5\n",
+        )
+        .unwrap();
+
+        let (ledger, diagnostics) =
+            super::load_transactions(fpath.to_str().unwrap(), FaultTolerance::SkipFaultyRows)
+                .unwrap();
+        std::fs::remove_file(&fpath).unwrap();
+
+        // the excessive withdrawal on line 3 is recorded as a diagnostic, not fatal
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].0, 3);
+
+        // the deposits on either side of it are still applied
+        assert_account(
+            &ledger.accounts[&CLIENT1],
+            d128!(2.0),
+            d128!(0),
+            d128!(2.0),
+            false,
+        );
+    }
+
+    #[test]
+    fn disputed_withdrawal_resolve_restores_original_state() {
+        let mut ledger = Ledger::new(true);
+
+        let tx = Transaction {
+            type_: TransactionType::Deposit,
+            client: CLIENT1,
+            id: 1,
+            amount: Some(d128!(0.0005)),
+        };
+        ledger.process_transaction(&tx).unwrap();
+
+        let tx = Transaction {
+            type_: TransactionType::Withdrawal,
+            client: CLIENT1,
+            id: 2,
+            amount: Some(d128!(0.0002)),
+        };
+        ledger.process_transaction(&tx).unwrap();
+        assert_account(
+            &ledger.accounts[&CLIENT1],
+            d128!(0.0003),
+            d128!(0),
+            d128!(0.0003),
+            false,
+        );
+
+        {
+            // dispute the withdrawal: funds are provisionally restored but held pending review
+            let tx = Transaction {
+                type_: TransactionType::Dispute,
+                client: CLIENT1,
+                id: 2,
+                amount: None,
+            };
+            ledger.process_transaction(&tx).unwrap();
+            assert_account(
+                &ledger.accounts[&CLIENT1],
+                d128!(0.0003),
+                d128!(0.0002),
+                d128!(0.0005),
+                false,
+            );
+        }
+
+        {
+            // resolve: the withdrawal stands, back to exactly where we were after it
+            let tx = Transaction {
+                type_: TransactionType::Resolve,
+                client: CLIENT1,
+                id: 2,
+                amount: None,
+            };
+            ledger.process_transaction(&tx).unwrap();
+            assert_account(
+                &ledger.accounts[&CLIENT1],
+                d128!(0.0003),
+                d128!(0),
+                d128!(0.0003),
+                false,
+            );
+        }
+    }
+
+    #[test]
+    fn disputed_withdrawal_chargeback_reverses_it() {
+        let mut ledger = Ledger::new(true);
+
+        let tx = Transaction {
+            type_: TransactionType::Deposit,
+            client: CLIENT1,
+            id: 1,
+            amount: Some(d128!(0.0005)),
+        };
+        ledger.process_transaction(&tx).unwrap();
+
+        let tx = Transaction {
+            type_: TransactionType::Withdrawal,
+            client: CLIENT1,
+            id: 2,
+            amount: Some(d128!(0.0002)),
+        };
+        ledger.process_transaction(&tx).unwrap();
+
+        for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+            let tx = Transaction {
+                type_: tx_type,
+                client: CLIENT1,
+                id: 2,
+                amount: None,
+            };
+            ledger.process_transaction(&tx).unwrap();
+        }
+
+        // the withdrawal is fully reversed and the account is frozen
+        assert_account(
+            &ledger.accounts[&CLIENT1],
+            d128!(0.0005),
+            d128!(0),
+            d128!(0.0005),
+            true,
+        );
+    }
+
     fn assert_account(
         account: &Account,
         available: Money,
@@ -1,4 +1,4 @@
-use std::io::stdout;
+use std::io::{stderr, stdout, Write};
 
 use anyhow::{self, bail, Context, Ok};
 
@@ -8,8 +8,15 @@ fn main() -> anyhow::Result<()> {
     let cmdline_params = parse_cmdline()
         .context("cannot parse command line parametes")?;
 
-    let ledger = accounting::load_transactions(&cmdline_params.transactions_fpath)
-        .context("cannot load transactions")?;
+    let (ledger, diagnostics) = accounting::load_transactions(
+        &cmdline_params.transactions_fpath,
+        cmdline_params.fault_tolerance,
+    )
+    .context("cannot load transactions")?;
+
+    for (line_number, error) in &diagnostics {
+        writeln!(stderr(), "line {line_number}: {error}").context("cannot report diagnostics")?;
+    }
 
     accounting::output_accounts(&ledger, &mut stdout())
         .context("cannot print accounts")?;
@@ -19,17 +26,23 @@ fn main() -> anyhow::Result<()> {
 
 struct CmdlineParams {
     transactions_fpath: String,
+    fault_tolerance: accounting::FaultTolerance,
 }
 
 fn parse_cmdline() -> anyhow::Result<CmdlineParams> {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        bail!("expected parameter: <file_path>");
-    } else {
-        Ok(CmdlineParams {
-            transactions_fpath: args[1].clone(),
-        })
-    }
+    let (transactions_fpath, fault_tolerance) = match args.as_slice() {
+        [_, fpath] => (fpath.clone(), accounting::FaultTolerance::Strict),
+        [_, fpath, flag] if flag == "--skip-faulty" => {
+            (fpath.clone(), accounting::FaultTolerance::SkipFaultyRows)
+        }
+        _ => bail!("expected parameters: <file_path> [--skip-faulty]"),
+    };
+
+    Ok(CmdlineParams {
+        transactions_fpath,
+        fault_tolerance,
+    })
 }
 
 #[cfg(test)]
@@ -40,15 +53,19 @@ mod tests {
 
     #[test]
     fn happy_path_e2e() {
-        let ledger = accounting::load_transactions("test_data/happy_path.csv").unwrap();
-        
+        let (ledger, diagnostics) = accounting::load_transactions(
+            "test_data/happy_path.csv",
+            accounting::FaultTolerance::Strict,
+        )
+        .unwrap();
+        assert!(diagnostics.is_empty());
+
         let mut output = vec![];
         accounting::output_accounts(&ledger, &mut output).unwrap();
         let output_string = String::from_utf8(output).unwrap();
-        let mut lines: Vec<&str> = output_string.split('\n').collect();
-        lines[1..4].sort(); // sorting counteracts the fact that random seed in HashMaps causes different order every run
-        
-        assert_eq!(lines.len(), 5); // header + 3 accounts + empty line at the end 
+        let lines: Vec<&str> = output_string.split('\n').collect();
+
+        assert_eq!(lines.len(), 5); // header + 3 accounts (sorted by client id) + empty line at the end
         assert_eq!(lines[0], "client,available,held,total,locked");
         assert_eq!(lines[1], "1,1.0001,0,1.0001,false");
         assert_eq!(lines[2], "2,4.0005,0.0000,4.0005,false");